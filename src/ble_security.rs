@@ -1,12 +1,27 @@
+use crate::ble_storage::BondStore;
 use crate::enums;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
 
+#[allow(clippy::type_complexity)]
 pub struct BLESecurity {
   passkey: u32,
+  bond_store: BondStore,
+
+  on_passkey_request: Option<Box<dyn FnMut() -> u32 + Send + Sync>>,
+  on_confirm_pin: Option<Box<dyn FnMut(u32) -> bool + Send + Sync>>,
+  on_authentication_complete: Option<Box<dyn FnMut(&esp_idf_sys::ble_gap_conn_desc) + Send + Sync>>,
 }
 
 impl BLESecurity {
   pub(crate) fn new() -> Self {
-    Self { passkey: 0 }
+    Self {
+      passkey: 0,
+      bond_store: BondStore::new(),
+      on_passkey_request: None,
+      on_confirm_pin: None,
+      on_authentication_complete: None,
+    }
   }
 
   pub fn set_auth(&mut self, bonding: bool, mitm: bool, sc: bool) -> &mut Self {
@@ -44,4 +59,98 @@ impl BLESecurity {
     unsafe { esp_idf_sys::ble_hs_cfg.sm_their_key_dist = resp_key.bits() };
     self
   }
+
+  /// Called when pairing needs a passkey: one we *display* for the peer to
+  /// type in (`Display*`), or one we must *input* (`KeyboardOnly`). Defaults to
+  /// the value set with [`set_passkey`](Self::set_passkey) when no callback is
+  /// registered.
+  pub fn on_passkey_request(&mut self, callback: impl FnMut() -> u32 + Send + Sync + 'static) -> &mut Self {
+    self.on_passkey_request = Some(Box::new(callback));
+    self
+  }
+
+  /// Called for Secure Connections numeric comparison: the displayed 6-digit
+  /// number is passed in and the callback returns whether it matches what the
+  /// user saw on the peer.
+  pub fn on_confirm_pin(&mut self, callback: impl FnMut(u32) -> bool + Send + Sync + 'static) -> &mut Self {
+    self.on_confirm_pin = Some(Box::new(callback));
+    self
+  }
+
+  /// Called once pairing/encryption has finished, successfully or not; check
+  /// `desc.sec_state` to tell the two apart.
+  pub fn on_authentication_complete(
+    &mut self,
+    callback: impl FnMut(&esp_idf_sys::ble_gap_conn_desc) + Send + Sync + 'static,
+  ) -> &mut Self {
+    self.on_authentication_complete = Some(Box::new(callback));
+    self
+  }
+
+  pub(crate) fn handle_passkey_action(
+    &mut self,
+    conn_handle: u16,
+    params: &esp_idf_sys::ble_gap_passkey_params,
+  ) {
+    let mut io: esp_idf_sys::ble_sm_io = unsafe { core::mem::zeroed() };
+    io.action = params.action as _;
+
+    match params.action as _ {
+      esp_idf_sys::BLE_SM_IOACT_DISP => {
+        let passkey = self
+          .on_passkey_request
+          .as_mut()
+          .map(|cb| cb())
+          .unwrap_or(self.passkey);
+        io.__bindgen_anon_1.passkey = passkey;
+      }
+      esp_idf_sys::BLE_SM_IOACT_INPUT => {
+        let passkey = self
+          .on_passkey_request
+          .as_mut()
+          .map(|cb| cb())
+          .unwrap_or(self.passkey);
+        io.__bindgen_anon_1.passkey = passkey;
+      }
+      esp_idf_sys::BLE_SM_IOACT_NUMCMP => {
+        let confirm = self
+          .on_confirm_pin
+          .as_mut()
+          .map(|cb| cb(params.numcmp))
+          .unwrap_or(false);
+        io.__bindgen_anon_1.numcmp_accept = confirm as _;
+      }
+      _ => return,
+    }
+
+    unsafe {
+      esp_idf_sys::ble_sm_inject_io(conn_handle, &mut io);
+    }
+  }
+
+  pub(crate) fn handle_authentication_complete(&mut self, desc: &esp_idf_sys::ble_gap_conn_desc) {
+    if let Some(callback) = self.on_authentication_complete.as_mut() {
+      callback(desc);
+    }
+  }
+
+  /// Persists bonded keys to NVS across reboots, so a bonded central doesn't
+  /// have to re-pair every time the device power-cycles. Reloads any
+  /// previously-stored bonds immediately when turned on.
+  pub fn set_persist(&mut self, persist: bool) -> &mut Self {
+    self.bond_store.set_persist(persist);
+    self
+  }
+
+  pub(crate) fn bond_store(&mut self) -> &mut BondStore {
+    &mut self.bond_store
+  }
+
+  pub(crate) fn bonded_addresses(&self) -> Vec<esp_idf_sys::ble_addr_t> {
+    self.bond_store.bonded_addresses()
+  }
+
+  pub(crate) fn delete_bond(&mut self, addr: &esp_idf_sys::ble_addr_t) {
+    self.bond_store.delete_bond(addr)
+  }
 }
@@ -0,0 +1,287 @@
+use alloc::vec::Vec;
+use core::ffi::c_void;
+
+/// Fixed-capacity bond table, matching NimBLE's own `CONFIG_BT_NIMBLE_MAX_BONDS`
+/// limit, so we never grow unbounded no matter how many peers churn through.
+/// This counts distinct *peers*, not records - one bond spans several records
+/// (OUR_SEC, PEER_SEC, and any CCCDs), so evicting has to drop all of a
+/// peer's records together or we'd leave a half-bond behind.
+const MAX_BONDS: usize = esp_idf_sys::CONFIG_BT_NIMBLE_MAX_BONDS as usize;
+
+const NVS_NAMESPACE: &[u8] = b"nimble_bond\0";
+const NVS_KEY: &[u8] = b"bonds\0";
+
+/// One persisted `ble_store` record (security material or a CCCD
+/// subscription), keyed by the peer's identity address. We don't interpret
+/// the payload beyond that - it's treated as an opaque `ble_store_value` blob
+/// and handed back to NimBLE byte-for-byte, since NimBLE itself is the only
+/// thing that needs to make sense of it.
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct BondRecord {
+  obj_type: u32,
+  addr: esp_idf_sys::ble_addr_t,
+  value: esp_idf_sys::ble_store_value,
+}
+
+fn addr_eq(a: &esp_idf_sys::ble_addr_t, b: &esp_idf_sys::ble_addr_t) -> bool {
+  a.type_ == b.type_ && a.val == b.val
+}
+
+pub(crate) struct BondStore {
+  persist: bool,
+  records: Vec<BondRecord>,
+}
+
+impl BondStore {
+  pub(crate) fn new() -> Self {
+    Self {
+      persist: false,
+      records: Vec::new(),
+    }
+  }
+
+  /// Enables NVS persistence, reloading any previously-bonded keys and
+  /// wiring the `ble_store_*` callbacks into `ble_hs_cfg` so future pairings
+  /// are written through to flash.
+  pub(crate) fn set_persist(&mut self, persist: bool) {
+    self.persist = persist;
+
+    unsafe {
+      esp_idf_sys::ble_hs_cfg.store_status_cb = Some(Self::status_cb);
+      esp_idf_sys::ble_hs_cfg.store_read_cb = Some(Self::read_cb);
+      esp_idf_sys::ble_hs_cfg.store_write_cb = Some(Self::write_cb);
+      esp_idf_sys::ble_hs_cfg.store_delete_cb = Some(Self::delete_cb);
+    }
+
+    if persist {
+      self.load();
+    }
+  }
+
+  pub(crate) fn bonded_addresses(&self) -> Vec<esp_idf_sys::ble_addr_t> {
+    let mut addrs: Vec<esp_idf_sys::ble_addr_t> = Vec::new();
+    for record in &self.records {
+      if record.obj_type == esp_idf_sys::BLE_STORE_OBJ_TYPE_PEER_SEC
+        && !addrs.iter().any(|a| addr_eq(a, &record.addr))
+      {
+        addrs.push(record.addr);
+      }
+    }
+    addrs
+  }
+
+  pub(crate) fn delete_bond(&mut self, addr: &esp_idf_sys::ble_addr_t) {
+    self.records.retain(|r| !addr_eq(&r.addr, addr));
+    self.save();
+  }
+
+  fn current() -> &'static mut Self {
+    crate::BLEDevice::take().get_security().bond_store()
+  }
+
+  fn peer_count(&self) -> usize {
+    let mut seen: Vec<esp_idf_sys::ble_addr_t> = Vec::new();
+    for record in &self.records {
+      if !seen.iter().any(|a| addr_eq(a, &record.addr)) {
+        seen.push(record.addr);
+      }
+    }
+    seen.len()
+  }
+
+  /// Drops every record belonging to the oldest bonded peer (not just the
+  /// oldest record), so we never leave a bond half-evicted.
+  fn evict_oldest_peer(&mut self) {
+    if let Some(oldest) = self.records.first().map(|r| r.addr) {
+      self.records.retain(|r| !addr_eq(&r.addr, &oldest));
+    }
+  }
+
+  fn insert(&mut self, obj_type: u32, addr: esp_idf_sys::ble_addr_t, value: esp_idf_sys::ble_store_value) {
+    if let Some(existing) = self
+      .records
+      .iter_mut()
+      .find(|r| r.obj_type == obj_type && addr_eq(&r.addr, &addr))
+    {
+      existing.value = value;
+    } else {
+      let is_new_peer = !self.records.iter().any(|r| addr_eq(&r.addr, &addr));
+      if is_new_peer && self.peer_count() >= MAX_BONDS {
+        // Fixed-capacity table: make room for the newest bond rather than
+        // silently refusing it, same as NimBLE's own ring behaviour.
+        self.evict_oldest_peer();
+      }
+      self.records.push(BondRecord { obj_type, addr, value });
+    }
+
+    self.save();
+  }
+
+  fn load(&mut self) {
+    self.records = nvs::load(NVS_NAMESPACE, NVS_KEY).unwrap_or_default();
+  }
+
+  fn save(&self) {
+    if self.persist {
+      nvs::save(NVS_NAMESPACE, NVS_KEY, &self.records);
+    }
+  }
+
+  extern "C" fn status_cb(event: *mut esp_idf_sys::ble_store_status_event, _arg: *mut c_void) -> i32 {
+    let event = unsafe { &*event };
+    if event.event_code == esp_idf_sys::BLE_STORE_EVENT_OVERFLOW {
+      let store = Self::current();
+      store.evict_oldest_peer();
+      store.save();
+    }
+    0
+  }
+
+  extern "C" fn read_cb(
+    obj_type: i32,
+    key: *const esp_idf_sys::ble_store_key,
+    value: *mut esp_idf_sys::ble_store_value,
+  ) -> i32 {
+    let store = Self::current();
+    let key = unsafe { &*key };
+    let addr = unsafe { store_key_addr(obj_type as _, key) };
+    let idx = unsafe { store_key_idx(obj_type as _, key) };
+
+    match find_by_key(&store.records, obj_type as u32, &addr, idx) {
+      Some(i) => {
+        unsafe { *value = store.records[i].value };
+        0
+      }
+      None => esp_idf_sys::BLE_HS_ENOENT as i32,
+    }
+  }
+
+  extern "C" fn write_cb(obj_type: i32, value: *const esp_idf_sys::ble_store_value) -> i32 {
+    let store = Self::current();
+    let value = unsafe { *value };
+    let addr = unsafe { store_value_addr(obj_type as _, &value) };
+    store.insert(obj_type as _, addr, value);
+    0
+  }
+
+  extern "C" fn delete_cb(obj_type: i32, key: *const esp_idf_sys::ble_store_key) -> i32 {
+    let store = Self::current();
+    let key = unsafe { &*key };
+    let addr = unsafe { store_key_addr(obj_type as _, key) };
+    let idx = unsafe { store_key_idx(obj_type as _, key) };
+
+    match find_by_key(&store.records, obj_type as u32, &addr, idx) {
+      Some(i) => {
+        store.records.remove(i);
+        store.save();
+        0
+      }
+      None => esp_idf_sys::BLE_HS_ENOENT as i32,
+    }
+  }
+}
+
+/// `BLE_ADDR_ANY` - an absent/unknown address, like the `ble_addr_any`
+/// constant in upstream NimBLE.
+fn addr_is_any(addr: &esp_idf_sys::ble_addr_t) -> bool {
+  addr.type_ == 0 && addr.val == [0u8; 6]
+}
+
+unsafe fn store_key_addr(obj_type: u32, key: &esp_idf_sys::ble_store_key) -> esp_idf_sys::ble_addr_t {
+  match obj_type {
+    esp_idf_sys::BLE_STORE_OBJ_TYPE_CCCD => key.cccd.peer_addr,
+    _ => key.sec.peer_addr,
+  }
+}
+
+unsafe fn store_key_idx(obj_type: u32, key: &esp_idf_sys::ble_store_key) -> u8 {
+  match obj_type {
+    esp_idf_sys::BLE_STORE_OBJ_TYPE_CCCD => key.cccd.idx,
+    _ => key.sec.idx,
+  }
+}
+
+unsafe fn store_value_addr(obj_type: u32, value: &esp_idf_sys::ble_store_value) -> esp_idf_sys::ble_addr_t {
+  match obj_type {
+    esp_idf_sys::BLE_STORE_OBJ_TYPE_CCCD => value.cccd.peer_addr,
+    _ => value.sec.peer_addr,
+  }
+}
+
+/// Resolves a `ble_store_key` to a record index. With `peer_addr` present
+/// only that peer's records match; with it absent (`BLE_ADDR_ANY`) every
+/// peer's do. Either way `idx` picks the `idx`-th match, which is how NimBLE
+/// walks the table at startup to resolve bonded peers' RPAs and restore
+/// CCCDs. Mirrors `ble_store_ram_find_sec` in upstream NimBLE.
+fn find_by_key(
+  records: &[BondRecord],
+  obj_type: u32,
+  addr: &esp_idf_sys::ble_addr_t,
+  idx: u8,
+) -> Option<usize> {
+  let any = addr_is_any(addr);
+  records
+    .iter()
+    .enumerate()
+    .filter(|(_, r)| r.obj_type == obj_type && (any || addr_eq(&r.addr, addr)))
+    .nth(idx as usize)
+    .map(|(i, _)| i)
+}
+
+/// Minimal blob persistence on top of the NVS flat-blob API; we store the
+/// whole bond table under one key rather than one NVS entry per record since
+/// the table is small and always rewritten as a unit anyway.
+mod nvs {
+  use super::BondRecord;
+  use alloc::vec::Vec;
+
+  pub(super) fn load(namespace: &[u8], key: &[u8]) -> Option<Vec<BondRecord>> {
+    unsafe {
+      let mut handle: esp_idf_sys::nvs_handle_t = 0;
+      if esp_idf_sys::nvs_open(
+        namespace.as_ptr() as _,
+        esp_idf_sys::nvs_open_mode_t_NVS_READONLY,
+        &mut handle,
+      ) != esp_idf_sys::ESP_OK as i32
+      {
+        return None;
+      }
+
+      let mut len: usize = 0;
+      if esp_idf_sys::nvs_get_blob(handle, key.as_ptr() as _, core::ptr::null_mut(), &mut len)
+        != esp_idf_sys::ESP_OK as i32
+        || len == 0
+      {
+        esp_idf_sys::nvs_close(handle);
+        return None;
+      }
+
+      let count = len / core::mem::size_of::<BondRecord>();
+      let mut records = alloc::vec![core::mem::zeroed::<BondRecord>(); count];
+      esp_idf_sys::nvs_get_blob(handle, key.as_ptr() as _, records.as_mut_ptr() as _, &mut len);
+      esp_idf_sys::nvs_close(handle);
+
+      Some(records)
+    }
+  }
+
+  pub(super) fn save(namespace: &[u8], key: &[u8], records: &[BondRecord]) {
+    unsafe {
+      let mut handle: esp_idf_sys::nvs_handle_t = 0;
+      if esp_idf_sys::nvs_open(
+        namespace.as_ptr() as _,
+        esp_idf_sys::nvs_open_mode_t_NVS_READWRITE,
+        &mut handle,
+      ) != esp_idf_sys::ESP_OK as i32
+      {
+        return;
+      }
+
+      let bytes = core::mem::size_of_val(records);
+      esp_idf_sys::nvs_set_blob(handle, key.as_ptr() as _, records.as_ptr() as _, bytes as _);
+      esp_idf_sys::nvs_commit(handle);
+      esp_idf_sys::nvs_close(handle);
+    }
+  }
+}
@@ -0,0 +1,59 @@
+/// Default ATT MTU before an exchange has happened, per the Core spec.
+const BLE_ATT_MTU_DFLT: u16 = 23;
+
+/// Per-connection state cached from GAP events. `BLEServer` used to track
+/// connections as a bare `Vec<u16>` of handles, which left nowhere to hang
+/// this - callers had to re-derive MTU/params from a fresh
+/// `ble_gap_conn_find` call (or guess a 20-byte payload) on every notify.
+#[derive(Clone, Copy)]
+pub struct BLEConnDesc {
+  pub conn_handle: u16,
+  pub address: esp_idf_sys::ble_addr_t,
+  pub mtu: u16,
+  pub interval: u16,
+  pub latency: u16,
+  pub timeout: u16,
+  pub bonded: bool,
+  pub encrypted: bool,
+}
+
+impl BLEConnDesc {
+  pub(crate) fn new(desc: &esp_idf_sys::ble_gap_conn_desc) -> Self {
+    Self {
+      conn_handle: desc.conn_handle,
+      address: desc.peer_id_addr,
+      mtu: BLE_ATT_MTU_DFLT,
+      interval: desc.conn_itvl,
+      latency: desc.conn_latency,
+      timeout: desc.supervision_timeout,
+      bonded: desc.sec_state.bonded() != 0,
+      encrypted: desc.sec_state.encrypted() != 0,
+    }
+  }
+
+  /// Placeholder record for a connection whose `ble_gap_conn_desc` couldn't
+  /// be looked up at CONNECT time. We still need to track the handle so
+  /// `connected_count()` and the DISCONNECT arm see it; MTU, connection
+  /// parameters and security flags fill in from later events (MTU,
+  /// CONN_UPDATE, ENC_CHANGE) if any arrive, while `address` stays zeroed.
+  pub(crate) fn unknown(conn_handle: u16) -> Self {
+    Self {
+      conn_handle,
+      address: unsafe { core::mem::zeroed() },
+      mtu: BLE_ATT_MTU_DFLT,
+      interval: 0,
+      latency: 0,
+      timeout: 0,
+      bonded: false,
+      encrypted: false,
+    }
+  }
+
+  pub(crate) fn update_from_desc(&mut self, desc: &esp_idf_sys::ble_gap_conn_desc) {
+    self.interval = desc.conn_itvl;
+    self.latency = desc.conn_latency;
+    self.timeout = desc.supervision_timeout;
+    self.bonded = desc.sec_state.bonded() != 0;
+    self.encrypted = desc.sec_state.encrypted() != 0;
+  }
+}
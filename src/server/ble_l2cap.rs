@@ -0,0 +1,213 @@
+use super::BLEServer;
+use crate::{ble, BLEReturnCode};
+use alloc::boxed::Box;
+use core::ffi::c_void;
+
+/// Supplies a fresh, empty receive buffer for the channel's next SDU. This -
+/// not a manual credit counter - is how NimBLE actually replenishes a peer's
+/// send credits: granting `ble_l2cap_recv_ready` a buffer is what lets NimBLE
+/// tell the peer it may send more. Skipping this call is exactly what leaves
+/// a channel stalled after its first SDU.
+fn resupply_rx_buffer(chan: *mut esp_idf_sys::ble_l2cap_chan) {
+  unsafe {
+    let rx = esp_idf_sys::os_msys_get_pkthdr(0, 0);
+    esp_idf_sys::ble_l2cap_recv_ready(chan, rx);
+  }
+}
+
+/// Mirrors the `OS_MBUF_PKTHDR`/`OS_MBUF_PKTLEN` macros: the packet header
+/// (and with it the *total* chained length) sits right after the `os_mbuf`
+/// struct, not in `om_len`, which is only the first segment's length.
+unsafe fn mbuf_pkt_len(om: *const esp_idf_sys::os_mbuf) -> usize {
+  let pkthdr = (om as *const u8).add(core::mem::size_of::<esp_idf_sys::os_mbuf>())
+    as *const esp_idf_sys::os_mbuf_pkthdr;
+  (*pkthdr).omp_len as usize
+}
+
+/// A single L2CAP credit-based connection-oriented channel.
+///
+/// Created either by accepting an incoming connection on a PSM registered
+/// with [`BLEL2cap::listen`], or by initiating one with [`BLEL2cap::connect`].
+#[allow(clippy::type_complexity)]
+pub struct BLEL2capChannel {
+  pub(crate) conn_handle: u16,
+  pub(crate) psm: u16,
+  mtu: u16,
+  chan: *mut esp_idf_sys::ble_l2cap_chan,
+
+  on_recv: Option<Box<dyn FnMut(&mut BLEL2capChannel, &[u8]) + Send + Sync>>,
+  on_tx_unstalled: Option<Box<dyn FnMut(&mut BLEL2capChannel) + Send + Sync>>,
+}
+
+unsafe impl Send for BLEL2capChannel {}
+unsafe impl Sync for BLEL2capChannel {}
+
+impl BLEL2capChannel {
+  fn new(conn_handle: u16, psm: u16, mtu: u16, chan: *mut esp_idf_sys::ble_l2cap_chan) -> Self {
+    Self {
+      conn_handle,
+      psm,
+      mtu,
+      chan,
+      on_recv: None,
+      on_tx_unstalled: None,
+    }
+  }
+
+  /// The negotiated SDU MTU for this channel.
+  pub fn mtu(&self) -> u16 {
+    self.mtu
+  }
+
+  /// The PSM this channel was opened on.
+  pub fn psm(&self) -> u16 {
+    self.psm
+  }
+
+  pub fn on_recv(&mut self, callback: impl FnMut(&mut Self, &[u8]) + Send + Sync + 'static) -> &mut Self {
+    self.on_recv = Some(Box::new(callback));
+    self
+  }
+
+  /// Called once the peer has granted us more credits after a previous
+  /// [`send`](Self::send) returned `BLE_HS_EAGAIN` / `BLE_HS_ESTALLED`.
+  pub fn on_tx_unstalled(&mut self, callback: impl FnMut(&mut Self) + Send + Sync + 'static) -> &mut Self {
+    self.on_tx_unstalled = Some(Box::new(callback));
+    self
+  }
+
+  /// Sends one SDU. Returns `Err` (with the underlying NimBLE status) if the
+  /// peer is out of credits; retry once [`on_tx_unstalled`](Self::on_tx_unstalled) fires.
+  pub fn send(&mut self, data: &[u8]) -> Result<(), BLEReturnCode> {
+    unsafe {
+      let om = esp_idf_sys::ble_hs_mbuf_from_flat(data.as_ptr() as _, data.len() as _);
+      if om.is_null() {
+        return Err(BLEReturnCode::from(esp_idf_sys::BLE_HS_ENOMEM as i32));
+      }
+      ble!(esp_idf_sys::ble_l2cap_send(self.chan, om))
+    }
+  }
+
+  fn handle_data_received(&mut self, om: *mut esp_idf_sys::os_mbuf) {
+    unsafe {
+      let len = mbuf_pkt_len(om);
+      let mut buf = alloc::vec![0u8; len];
+      esp_idf_sys::ble_hs_mbuf_to_flat(om, buf.as_mut_ptr() as _, len as _, core::ptr::null_mut());
+      esp_idf_sys::os_mbuf_free_chain(om);
+
+      if let Some(mut callback) = self.on_recv.take() {
+        callback(self, &buf);
+        self.on_recv = Some(callback);
+      }
+    }
+
+    // Hand NimBLE a new buffer for the next SDU - this is what actually
+    // grants the peer fresh credits, not a counter we maintain ourselves.
+    resupply_rx_buffer(self.chan);
+  }
+}
+
+/// L2CAP credit-based connection-oriented channel (CoC) entry point, used
+/// alongside [`BLEServer`](super::BLEServer) to stream bulk data (firmware
+/// upload, sensor dumps, ...) without the 20-ish byte ceiling of GATT
+/// notifications.
+pub struct BLEL2cap;
+
+impl BLEL2cap {
+  /// Registers a PSM so incoming channel requests are accepted automatically.
+  /// Accepted channels show up through [`BLEServer::l2cap_channels`].
+  pub fn listen(psm: u16, mtu: u16) -> Result<(), BLEReturnCode> {
+    unsafe {
+      ble!(esp_idf_sys::ble_l2cap_create_server(
+        psm,
+        mtu as _,
+        Some(Self::handle_l2cap_event),
+        core::ptr::null_mut(),
+      ))
+    }
+  }
+
+  /// Initiates a channel to `psm` over an already-established connection.
+  pub fn connect(conn_handle: u16, psm: u16, mtu: u16) -> Result<(), BLEReturnCode> {
+    unsafe {
+      ble!(esp_idf_sys::ble_l2cap_connect(
+        conn_handle,
+        psm,
+        mtu as _,
+        Some(Self::handle_l2cap_event),
+        // Non-null `cb_arg` marks channels we initiated; `listen` passes null.
+        1 as *mut c_void,
+      ))
+    }
+  }
+
+  pub(crate) extern "C" fn handle_l2cap_event(
+    event: *mut esp_idf_sys::ble_l2cap_event,
+    arg: *mut c_void,
+  ) -> i32 {
+    let event = unsafe { &*event };
+    let server = crate::BLEDevice::take().get_server();
+
+    match event.type_ as _ {
+      esp_idf_sys::BLE_L2CAP_EVENT_COC_CONNECTED => {
+        let connect = unsafe { &event.__bindgen_anon_1.connect };
+        if connect.status == 0 {
+          let mut info = unsafe { core::mem::zeroed::<esp_idf_sys::ble_l2cap_chan_info>() };
+          unsafe { esp_idf_sys::ble_l2cap_get_chan_info(connect.chan, &mut info) };
+
+          let channel = BLEL2capChannel::new(
+            connect.conn_handle,
+            info.psm,
+            info.peer_coc_mtu,
+            connect.chan,
+          );
+          server.l2cap_channels.push(channel);
+
+          // We're the initiator (no preceding ACCEPT event for us) - supply
+          // the first rx buffer ourselves now that the channel is up.
+          if !arg.is_null() {
+            resupply_rx_buffer(connect.chan);
+          }
+        }
+      }
+      esp_idf_sys::BLE_L2CAP_EVENT_COC_DISCONNECTED => {
+        let disconnect = unsafe { &event.__bindgen_anon_1.disconnect };
+        server
+          .l2cap_channels
+          .retain(|x| x.chan != disconnect.chan);
+      }
+      esp_idf_sys::BLE_L2CAP_EVENT_COC_ACCEPT => {
+        let accept = unsafe { &event.__bindgen_anon_1.accept };
+        // Acceptor side: NimBLE can't finish establishing the channel until
+        // we've handed it an rx buffer to receive into.
+        resupply_rx_buffer(accept.chan);
+      }
+      esp_idf_sys::BLE_L2CAP_EVENT_COC_DATA_RECEIVED => {
+        let data_received = unsafe { &event.__bindgen_anon_1.receive };
+        if let Some(channel) = server
+          .l2cap_channels
+          .iter_mut()
+          .find(|x| x.chan == data_received.chan)
+        {
+          channel.handle_data_received(data_received.sdu_rx);
+        }
+      }
+      esp_idf_sys::BLE_L2CAP_EVENT_COC_TX_UNSTALLED => {
+        let tx_unstalled = unsafe { &event.__bindgen_anon_1.tx_unstalled };
+        if let Some(channel) = server
+          .l2cap_channels
+          .iter_mut()
+          .find(|x| x.chan == tx_unstalled.chan)
+        {
+          if let Some(mut callback) = channel.on_tx_unstalled.take() {
+            callback(channel);
+            channel.on_tx_unstalled = Some(callback);
+          }
+        }
+      }
+      _ => {}
+    }
+
+    0
+  }
+}
@@ -1,4 +1,6 @@
+use super::ble_conn_desc::BLEConnDesc;
 use super::ble_gap_conn_find;
+use super::ble_l2cap::BLEL2capChannel;
 use crate::{
   ble,
   utilities::{mutex::Mutex, BleUuid},
@@ -15,11 +17,12 @@ pub struct BLEServer {
   advertise_on_disconnect: bool,
   services: Vec<Arc<Mutex<BLEService>>>,
   notify_characteristic: Vec<&'static mut BLECharacteristic>,
-  connections: Vec<u16>,
+  connections: Vec<BLEConnDesc>,
   indicate_wait: [u16; esp_idf_sys::CONFIG_BT_NIMBLE_MAX_CONNECTIONS as _],
+  pub(crate) l2cap_channels: Vec<BLEL2capChannel>,
 
   on_connect: Option<Box<dyn FnMut(&esp_idf_sys::ble_gap_conn_desc) + Send + Sync>>,
-  on_disconnect: Option<Box<dyn FnMut(&esp_idf_sys::ble_gap_conn_desc) + Send + Sync>>,
+  on_disconnect: Option<Box<dyn FnMut(&esp_idf_sys::ble_gap_conn_desc, BLEReturnCode) + Send + Sync>>,
 }
 
 impl BLEServer {
@@ -31,6 +34,7 @@ impl BLEServer {
       notify_characteristic: Vec::new(),
       connections: Vec::new(),
       indicate_wait: [BLE_HS_CONN_HANDLE_NONE; esp_idf_sys::CONFIG_BT_NIMBLE_MAX_CONNECTIONS as _],
+      l2cap_channels: Vec::new(),
       on_connect: None,
       on_disconnect: None,
     }
@@ -79,6 +83,60 @@ impl BLEServer {
     self.connections.len()
   }
 
+  /// Currently open L2CAP CoC channels, across all connections.
+  pub fn l2cap_channels(&mut self) -> &mut Vec<BLEL2capChannel> {
+    &mut self.l2cap_channels
+  }
+
+  /// Terminates a connection with the default reason (`BLE_ERR_REM_USER_CONN_TERM`).
+  pub fn disconnect(&self, conn_handle: u16) -> Result<(), BLEReturnCode> {
+    self.disconnect_with_reason(conn_handle, esp_idf_sys::BLE_ERR_REM_USER_CONN_TERM as _)
+  }
+
+  /// Terminates a connection, signalling `reason` (an HCI error code) to the peer,
+  /// e.g. after an auth failure.
+  pub fn disconnect_with_reason(&self, conn_handle: u16, reason: u8) -> Result<(), BLEReturnCode> {
+    unsafe { ble!(esp_idf_sys::ble_gap_terminate(conn_handle, reason)) }
+  }
+
+  /// Identity addresses of all peers currently bonded, useful for enforcing
+  /// "only accept already-bonded centrals" or showing a pairing whitelist.
+  pub fn bonded_addresses(&self) -> Vec<esp_idf_sys::ble_addr_t> {
+    BLEDevice::take().get_security().bonded_addresses()
+  }
+
+  /// Forgets a bonded peer, so it must pair again before reconnecting.
+  pub fn delete_bond(&self, addr: &esp_idf_sys::ble_addr_t) {
+    BLEDevice::take().get_security().delete_bond(addr)
+  }
+
+  /// Cached state (MTU, connection parameters, bonded/encrypted) for a connection.
+  pub fn conn_info(&self, conn_handle: u16) -> Option<&BLEConnDesc> {
+    self.connections.iter().find(|x| x.conn_handle == conn_handle)
+  }
+
+  /// Requests new connection parameters, e.g. a short interval for the
+  /// duration of a large GATT/L2CAP transfer, relaxed again afterwards.
+  pub fn update_conn_params(
+    &self,
+    conn_handle: u16,
+    min_interval: u16,
+    max_interval: u16,
+    latency: u16,
+    timeout: u16,
+  ) -> Result<(), BLEReturnCode> {
+    let mut params = esp_idf_sys::ble_gap_upd_params {
+      itvl_min: min_interval,
+      itvl_max: max_interval,
+      latency,
+      supervision_timeout: timeout,
+      min_ce_len: 0,
+      max_ce_len: 0,
+    };
+
+    unsafe { ble!(esp_idf_sys::ble_gap_update_params(conn_handle, &mut params)) }
+  }
+
   pub fn create_service(&mut self, uuid: BleUuid) -> Arc<Mutex<BLEService>> {
     let service = Arc::new(Mutex::new(BLEService::new(uuid)));
     self.services.push(service.clone());
@@ -96,12 +154,17 @@ impl BLEServer {
       esp_idf_sys::BLE_GAP_EVENT_CONNECT => {
         let connect = unsafe { &event.__bindgen_anon_1.connect };
         if connect.status == 0 {
-          server.connections.push(connect.conn_handle);
+          match ble_gap_conn_find(connect.conn_handle) {
+            Ok(desc) => {
+              server.connections.push(BLEConnDesc::new(&desc));
 
-          if let Ok(desc) = ble_gap_conn_find(connect.conn_handle) {
-            if let Some(callback) = server.on_connect.as_mut() {
-              callback(&desc);
+              if let Some(callback) = server.on_connect.as_mut() {
+                callback(&desc);
+              }
             }
+            // Still track the handle even without a descriptor, so it's
+            // counted and so DISCONNECT can find and remove it.
+            Err(_) => server.connections.push(BLEConnDesc::unknown(connect.conn_handle)),
           }
         }
       }
@@ -110,13 +173,17 @@ impl BLEServer {
         if let Some(idx) = server
           .connections
           .iter()
-          .position(|x| *x == disconnect.conn.conn_handle)
+          .position(|x| x.conn_handle == disconnect.conn.conn_handle)
         {
           server.connections.swap_remove(idx);
         }
 
+        server
+          .l2cap_channels
+          .retain(|x| x.conn_handle != disconnect.conn.conn_handle);
+
         if let Some(callback) = server.on_disconnect.as_mut() {
-          callback(&disconnect.conn);
+          callback(&disconnect.conn, BLEReturnCode::from(disconnect.reason as _));
         }
 
         if server.advertise_on_disconnect {
@@ -135,6 +202,54 @@ impl BLEServer {
           chr.subscribe(subscribe);
         }
       }
+      esp_idf_sys::BLE_GAP_EVENT_PASSKEY_ACTION => {
+        let passkey = unsafe { &event.__bindgen_anon_1.passkey };
+        BLEDevice::take()
+          .get_security()
+          .handle_passkey_action(passkey.conn_handle, &passkey.params);
+      }
+      esp_idf_sys::BLE_GAP_EVENT_ENC_CHANGE => {
+        let enc_change = unsafe { &event.__bindgen_anon_1.enc_change };
+        // Fired for both outcomes: a failed encryption change still hands us
+        // a valid conn_desc, whose sec_state tells success from failure.
+        if let Ok(desc) = ble_gap_conn_find(enc_change.conn_handle) {
+          if let Some(conn) = server
+            .connections
+            .iter_mut()
+            .find(|x| x.conn_handle == enc_change.conn_handle)
+          {
+            conn.update_from_desc(&desc);
+          }
+
+          BLEDevice::take()
+            .get_security()
+            .handle_authentication_complete(&desc);
+        }
+      }
+      esp_idf_sys::BLE_GAP_EVENT_MTU => {
+        let mtu = unsafe { &event.__bindgen_anon_1.mtu };
+        if let Some(conn) = server
+          .connections
+          .iter_mut()
+          .find(|x| x.conn_handle == mtu.conn_handle)
+        {
+          conn.mtu = mtu.value;
+        }
+      }
+      esp_idf_sys::BLE_GAP_EVENT_CONN_UPDATE => {
+        let conn_update = unsafe { &event.__bindgen_anon_1.conn_update };
+        if conn_update.status == 0 {
+          if let Ok(desc) = ble_gap_conn_find(conn_update.conn_handle) {
+            if let Some(conn) = server
+              .connections
+              .iter_mut()
+              .find(|x| x.conn_handle == conn_update.conn_handle)
+            {
+              conn.update_from_desc(&desc);
+            }
+          }
+        }
+      }
       esp_idf_sys::BLE_GAP_EVENT_NOTIFY_TX => {
         let notify_tx = unsafe { &event.__bindgen_anon_1.notify_tx };
         #[allow(unused_variables)]